@@ -23,20 +23,273 @@
 //! sure that the data overwritten it with zeros, and thus made unaccessible after free.
 //!
 //! - **Crash dump data leaks:** Due to zeroing data, crash dumps are often limited in exposure of
-//! sensitive data.
+//! sensitive data. On Linux, the backing pages are also explicitly marked `MADV_DONTDUMP`, so a
+//! core dump doesn't capture the secret even before it would be zeroed.
+//!
+//! - **Inheritance across `fork()`:** On Linux, the backing pages are marked `MADV_DONTFORK`, so
+//! a forked child process doesn't inherit the mapping at all.
+//!
+//! - **Stray reads of a "resting" secret:** Even a securely allocated secret is plaintext-readable
+//! at any instant an attacker can glance at the address space. To close that window, the backing
+//! page is kept `mprotect`ed to `PROT_NONE` except while a `borrow()`/`borrow_mut()` guard is
+//! alive.
+//!
+//! - **Overflows into the secret:** The secret is placed flush against an inaccessible guard page
+//! (so a linear over-run faults almost immediately) and is fenced on the other side by a random
+//! canary that is checked on every access, to catch an under/over-run that lands inside the
+//! usable page without reaching the guard.
 //!
 //! # NB!
 //!
 //! `SecBox` doesn't mean that the inner data is completely protected. You still need to make sure
 //! it is handled properly and not leaked by other means.
 
-#![feature(box_syntax, unique, core_intrinsics)]
+#![feature(box_syntax, unique, core_intrinsics, heap_api)]
 #![warn(missing_docs)]
 
+extern crate alloc;
 extern crate libc;
 
+use alloc::heap;
+use std::cell::Cell;
 use std::ptr::{self, Unique};
-use std::{mem, intrinsics, ops, fmt, slice};
+use std::{error, io, mem, intrinsics, ops, fmt, process, slice};
+
+/// The length, in bytes, of the canary written immediately before the secret.
+const CANARY_LEN: usize = 16;
+
+/// The size, in bytes, of a single virtual memory page on this system.
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Round `size` up to the next multiple of `align`, which must be a power of two.
+fn round_up(size: usize, align: usize) -> usize {
+    (size + align - 1) & !(align - 1)
+}
+
+/// Errors that can occur while fallibly constructing a `SecBox` (see
+/// [`try_new`](struct.SecBox.html#method.try_new) and
+/// [`try_from`](struct.SecBox.html#method.try_from)).
+#[derive(Debug)]
+pub enum SecBoxError {
+    /// The secure mapping could not be allocated (the underlying `mmap()` call, or the
+    /// `mprotect()` call that makes its usable region writable, failed).
+    AllocFailed(io::Error),
+    /// The secret could not be `mlock`ed into physical memory, e.g. because `RLIMIT_MEMLOCK` is
+    /// too low. Proceeding anyway would silently drop the swap-protection guarantee, so this is
+    /// reported rather than ignored.
+    MlockFailed(io::Error),
+    /// The `madvise()` call used to exclude the secret from core dumps (`MADV_DONTDUMP`) or from
+    /// being inherited across `fork()` (`MADV_DONTFORK`) failed. Linux-only.
+    MadviseFailed(io::Error),
+    /// The canary guarding the secret could not be seeded, because `/dev/urandom` could not be
+    /// opened or read. Without a canary, a buffer over/under-run into the secret would go
+    /// undetected, so this is reported rather than falling back to a predictable value.
+    CanarySeedFailed(io::Error),
+}
+
+impl fmt::Display for SecBoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SecBoxError::AllocFailed(ref e) => write!(f, "failed to allocate secure memory: {}", e),
+            SecBoxError::MlockFailed(ref e) => write!(f, "failed to mlock secure memory: {}", e),
+            SecBoxError::MadviseFailed(ref e) => write!(f, "failed to madvise secure memory: {}", e),
+            SecBoxError::CanarySeedFailed(ref e) => write!(f, "failed to seed the canary: {}", e),
+        }
+    }
+}
+
+impl error::Error for SecBoxError {
+    fn description(&self) -> &str {
+        match *self {
+            SecBoxError::AllocFailed(_) => "failed to allocate secure memory",
+            SecBoxError::MlockFailed(_) => "failed to mlock secure memory",
+            SecBoxError::MadviseFailed(_) => "failed to madvise secure memory",
+            SecBoxError::CanarySeedFailed(_) => "failed to seed the canary",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SecBoxError::AllocFailed(ref e) |
+            SecBoxError::MlockFailed(ref e) |
+            SecBoxError::MadviseFailed(ref e) |
+            SecBoxError::CanarySeedFailed(ref e) => Some(e),
+        }
+    }
+}
+
+/// The layout of a guarded allocation: a multi-page `mmap`ing with an inaccessible guard page on
+/// either side of a usable region holding a canary immediately followed by the secret.
+struct Layout {
+    /// Base address of the whole mapping, including both guard pages.
+    base: *mut u8,
+    /// Total length, in bytes, of the whole mapping.
+    full_len: usize,
+    /// Start of the usable (non-guard) region, i.e. `base` plus one page.
+    prot_base: *mut u8,
+    /// Length, in bytes, of the usable region (a whole number of pages).
+    prot_len: usize,
+    /// Pointer to where the secret itself begins, flush against the trailing guard page (modulo
+    /// the alignment `T` requires).
+    data_ptr: *mut u8,
+    /// Pointer to the `CANARY_LEN` canary bytes immediately preceding the secret.
+    canary_ptr: *mut u8,
+}
+
+/// Allocate a guarded mapping able to hold `size` bytes aligned to `align`.
+///
+/// The mapping looks like `[guard page][canary][secret][guard page]`, with the secret placed as
+/// close to the trailing guard page as `align` permits, so that a linear buffer over-run into it
+/// faults almost immediately instead of silently continuing into the secret.
+unsafe fn alloc_guarded(size: usize, align: usize) -> Result<Layout, SecBoxError> {
+    let page = page_size();
+    let usable_len = round_up(size + CANARY_LEN + align, page);
+    let full_len = page + usable_len + page;
+
+    let base = libc::mmap(ptr::null_mut(),
+                           full_len,
+                           libc::PROT_NONE,
+                           libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                           -1,
+                           0);
+    if base == libc::MAP_FAILED {
+        return Err(SecBoxError::AllocFailed(io::Error::last_os_error()));
+    }
+    let base = base as *mut u8;
+    let prot_base = base.offset(page as isize);
+
+    // Make the usable region (but not the surrounding guard pages) writable, so the canary and
+    // the secret can be written into it.
+    if libc::mprotect(prot_base as *mut libc::c_void, usable_len, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+        let e = SecBoxError::AllocFailed(io::Error::last_os_error());
+        libc::munmap(base as *mut libc::c_void, full_len);
+        return Err(e);
+    }
+
+    let raw_offset = usable_len - size;
+    let data_offset = raw_offset & !(align - 1);
+    let data_ptr = prot_base.offset(data_offset as isize);
+    let canary_ptr = data_ptr.offset(-(CANARY_LEN as isize));
+
+    Ok(Layout {
+        base: base,
+        full_len: full_len,
+        prot_base: prot_base,
+        prot_len: usable_len,
+        data_ptr: data_ptr,
+        canary_ptr: canary_ptr,
+    })
+}
+
+/// Attempt to `mlock` a region, reporting failure instead of silently ignoring it.
+fn mlock_region(base: *mut u8, len: usize) -> Result<(), SecBoxError> {
+    let ret = unsafe { libc::mlock(base as *const libc::c_void, len as libc::size_t) };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(SecBoxError::MlockFailed(io::Error::last_os_error()))
+    }
+}
+
+/// Exclude a region from core dumps (`MADV_DONTDUMP`) and from being inherited by a `fork()`ed
+/// child (`MADV_DONTFORK`).
+///
+/// A no-op returning `Ok(())` on platforms other than Linux, where these advice values don't
+/// exist.
+#[cfg(target_os = "linux")]
+fn madvise_secure(base: *mut u8, len: usize) -> Result<(), SecBoxError> {
+    unsafe {
+        if libc::madvise(base as *mut libc::c_void, len, libc::MADV_DONTDUMP) != 0 {
+            return Err(SecBoxError::MadviseFailed(io::Error::last_os_error()));
+        }
+        if libc::madvise(base as *mut libc::c_void, len, libc::MADV_DONTFORK) != 0 {
+            return Err(SecBoxError::MadviseFailed(io::Error::last_os_error()));
+        }
+    }
+
+    Ok(())
+}
+
+/// See the Linux implementation above; this platform has no equivalent advice values.
+#[cfg(not(target_os = "linux"))]
+fn madvise_secure(_base: *mut u8, _len: usize) -> Result<(), SecBoxError> {
+    Ok(())
+}
+
+/// Undo `madvise_secure`: make the region dumpable (`MADV_DODUMP`) and inheritable across
+/// `fork()` (`MADV_DOFORK`) again, before it is unmapped.
+///
+/// Best-effort; failures here aren't actionable during teardown, so they're ignored.
+#[cfg(target_os = "linux")]
+fn madvise_unsecure(base: *mut u8, len: usize) {
+    unsafe {
+        libc::madvise(base as *mut libc::c_void, len, libc::MADV_DODUMP);
+        libc::madvise(base as *mut libc::c_void, len, libc::MADV_DOFORK);
+    }
+}
+
+/// See the Linux implementation above; this platform has no equivalent advice values.
+#[cfg(not(target_os = "linux"))]
+fn madvise_unsecure(_base: *mut u8, _len: usize) {}
+
+/// Fill `buf` with random bytes read from `/dev/urandom`, for use as a canary.
+///
+/// Reports failure through `SecBoxError` instead of panicking, so that a `try_new`/`try_from`
+/// caller who cannot tolerate an unseeded (and thus useless) canary can refuse to proceed.
+fn fill_random(buf: &mut [u8]) -> Result<(), SecBoxError> {
+    unsafe {
+        let fd = libc::open(b"/dev/urandom\0".as_ptr() as *const libc::c_char, libc::O_RDONLY);
+        if fd < 0 {
+            return Err(SecBoxError::CanarySeedFailed(io::Error::last_os_error()));
+        }
+
+        let mut filled = 0;
+        while filled < buf.len() {
+            let n = libc::read(fd,
+                                buf[filled..].as_mut_ptr() as *mut libc::c_void,
+                                (buf.len() - filled) as libc::size_t);
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    // A signal arrived mid-read; this isn't a real failure, so retry rather
+                    // than giving up on a perfectly healthy /dev/urandom.
+                    continue;
+                }
+                libc::close(fd);
+                return Err(SecBoxError::CanarySeedFailed(err));
+            }
+            if n == 0 {
+                libc::close(fd);
+                return Err(SecBoxError::CanarySeedFailed(io::Error::last_os_error()));
+            }
+            filled += n as usize;
+        }
+
+        libc::close(fd);
+    }
+
+    Ok(())
+}
+
+/// Overwrite the data pointer of a (possibly fat) pointer, leaving any trailing metadata (a
+/// slice length or trait object vtable pointer) untouched.
+///
+/// This relies on the data pointer always being the first word of `*mut T`, true for both thin
+/// and fat pointers.
+unsafe fn set_data_ptr<T: ?Sized>(mut ptr: *mut T, data: *mut u8) -> *mut T {
+    ptr::write(&mut ptr as *mut *mut T as *mut *mut u8, data);
+    ptr
+}
+
+/// Overwrite the `size_of_val(&*ptr)` bytes at `ptr` with zeroes.
+///
+/// Broken out of `Drop for SecBox` so the zeroing step itself (as opposed to the full
+/// mmap/guard-page lifecycle around it) can be exercised directly, including from a test.
+unsafe fn zero_in_place<T: ?Sized>(ptr: *mut T) {
+    intrinsics::volatile_set_memory(ptr as *mut u8, 0, mem::size_of_val(&*ptr));
+}
 
 /// A secure box.
 ///
@@ -52,17 +305,42 @@ use std::{mem, intrinsics, ops, fmt, slice};
 ///    to read afterwards.
 /// 3. Non linearity. If you have a vector of `SecBox`es, they will not necessarily be lined up,
 ///    which mean that  if an attacker can read some part of the memory, it will rarely make sense.
+/// 4. Access gating. The backing page is `PROT_NONE` except while a `borrow()`/`borrow_mut()`
+///    guard is alive, limiting the window in which the plaintext is actually readable.
+/// 5. Guard pages and a canary. The secret sits flush against an inaccessible guard page, with a
+///    random canary immediately in front of it, so an over/under-run either faults outright or
+///    is caught the next time the canary is checked (which aborts the process).
+/// 6. Core dump and `fork()` exclusion. On Linux, the backing pages are `madvise`d
+///    `MADV_DONTDUMP`/`MADV_DONTFORK`, so neither a core dump nor a forked child can see them.
 ///
 /// # An important note
 ///
 /// Wrapping a primitive doesn't necessarily affect the inner data. Many primitves (like `Vec` and
 /// `Box`) are simply wrappers around a pointer to the inner data. For this reason you need to wrap
 /// the inner data (e.g. `Vec<SecBox<T>>` instaed of `SecBox<Vec<T>>`).
+///
+/// # Accessing the contents
+///
+/// `SecBox` does not implement `Deref`/`DerefMut`, since that would make the data permanently
+/// readable. Use [`borrow()`](#method.borrow) and [`borrow_mut()`](#method.borrow_mut) instead;
+/// both return RAII guards that grant access only for the guard's lifetime.
 pub struct SecBox<T: ?Sized> {
-    /// The inner pointer.
-    ///
-    /// We use a raw pointer so that we can handle the destructor manually.
+    /// The inner (typed) pointer, pointing at the secret within the backing allocation.
     inner: Unique<T>,
+    /// Base address of the backing `mmap`'d allocation, including both guard pages.
+    base: *mut u8,
+    /// Total length, in bytes, of the backing allocation.
+    full_len: usize,
+    /// Start of the protectable (non-guard) region.
+    prot_base: *mut u8,
+    /// Length, in bytes, of the protectable region.
+    prot_len: usize,
+    /// Pointer to the canary bytes immediately preceding the secret.
+    canary_ptr: *mut u8,
+    /// The expected canary value, generated once when the box was created.
+    canary: [u8; CANARY_LEN],
+    /// Number of outstanding `Ref`s. The backing page is `PROT_READ` while this is nonzero.
+    borrows: Cell<u8>,
 }
 
 impl<T: ?Sized> SecBox<T> {
@@ -70,99 +348,302 @@ impl<T: ?Sized> SecBox<T> {
     ///
     /// If you want to construct a unsized SecBox, you should convert a `Box` through the `From`
     /// trait.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the secure allocation cannot be made or locked; see
+    /// [`try_new`](#method.try_new) for a fallible version.
     #[inline(always)]
     pub fn new(inner: T) -> SecBox<T> where T: Sized {
-        let res = SecBox {
-            inner: unsafe { Unique::new(Box::into_raw(box mem::uninitialized::<T>())) },
-        };
-
-        // Lock the data.
-        res.memlock();
+        Self::try_new(inner).expect("SecBox::new: failed to secure the allocation")
+    }
 
-        // We set the inner data after the memlock to make sure that the data doesn't leave the memory.
+    /// Fallibly create a new `SecBox`.
+    ///
+    /// Unlike `new`, this does not panic if the allocation cannot be made or `mlock`ed (e.g.
+    /// because `RLIMIT_MEMLOCK` is too low); it reports the failure instead, so callers who
+    /// cannot tolerate an unlocked secret can refuse to proceed.
+    pub fn try_new(inner: T) -> Result<SecBox<T>, SecBoxError> where T: Sized {
         unsafe {
-            ptr::write(*res.inner, inner);
-        }
+            let size = mem::size_of::<T>();
+            let align = mem::align_of::<T>();
+            let layout = try!(alloc_guarded(size, align));
+
+            // Lock the data before anything is written into it.
+            if let Err(e) = mlock_region(layout.prot_base, layout.prot_len) {
+                libc::munmap(layout.base as *mut libc::c_void, layout.full_len);
+                return Err(e);
+            }
+
+            // Keep the secret out of core dumps and away from forked children.
+            if let Err(e) = madvise_secure(layout.base, layout.full_len) {
+                libc::munmap(layout.base as *mut libc::c_void, layout.full_len);
+                return Err(e);
+            }
+
+            let mut canary = [0u8; CANARY_LEN];
+            if let Err(e) = fill_random(&mut canary) {
+                libc::munmap(layout.base as *mut libc::c_void, layout.full_len);
+                return Err(e);
+            }
+            ptr::copy_nonoverlapping(canary.as_ptr(), layout.canary_ptr, CANARY_LEN);
+
+            let typed = layout.data_ptr as *mut T;
 
-        res
+            // We set the inner data after the memlock to make sure that the data doesn't leave the memory.
+            ptr::write(typed, inner);
+
+            let res = SecBox {
+                inner: Unique::new(typed),
+                base: layout.base,
+                full_len: layout.full_len,
+                prot_base: layout.prot_base,
+                prot_len: layout.prot_len,
+                canary_ptr: layout.canary_ptr,
+                canary: canary,
+                borrows: Cell::new(0),
+            };
+
+            // Nobody is borrowing yet, so the page goes straight to inaccessible.
+            res.protect(libc::PROT_NONE);
+
+            Ok(res)
+        }
     }
 
     /// Get the inner value of this `SecBox`.
     ///
     /// Take care. This moves the value from a secure space to the stack, allowing the data to
     /// reside in swap RAM.
-    pub fn into_inner(self) -> T where T: Sized {
+    pub fn into_inner(mut self) -> T where T: Sized {
         unsafe {
+            self.protect(libc::PROT_READ | libc::PROT_WRITE);
+
             // Read the inner.
-            let res = ptr::read(*self.inner);
+            let res = ptr::read(self.inner.get());
             // Zero it.
-            ptr::write_volatile(*self.inner, mem::zeroed());
-            // Unlock the memory.
+            ptr::write_volatile(self.inner.get_mut(), mem::zeroed());
+            // Unlock, undo the madvise hints, and unmap the memory.
             self.memunlock();
+            madvise_unsecure(self.base, self.full_len);
+            libc::munmap(self.base as *mut libc::c_void, self.full_len);
+
+            // We've already torn down the allocation by hand; don't let `Drop` run on it too.
+            mem::forget(self);
 
             res
         }
     }
 
-    /// Memlock the inner data.
-    fn memlock(&self) {
-        unsafe {
-            libc::mlock(&**self as *const T as *const libc::c_void,
-                        mem::size_of_val(&**self) as libc::size_t);
-        };
+    /// Borrow the contents for reading.
+    ///
+    /// This flips the backing page to `PROT_READ` for the lifetime of the returned guard.
+    /// Overlapping calls to `borrow()` are allowed: a refcount is kept, and the page only reverts
+    /// to `PROT_NONE` once the last outstanding `Ref` is dropped.
+    pub fn borrow(&self) -> Ref<T> {
+        let n = self.borrows.get();
+        if n == 0 {
+            self.protect(libc::PROT_READ);
+        } else {
+            // The page is already readable (from an earlier overlapping `borrow()`), so
+            // `protect()` won't run here to check the canary on our behalf. Check it ourselves,
+            // since every access should be able to observe tampering, not just the first.
+            self.check_canary();
+        }
+        self.borrows.set(n + 1);
+
+        Ref { inner: self }
+    }
+
+    /// Borrow the contents for reading and writing.
+    ///
+    /// This flips the backing page to `PROT_READ | PROT_WRITE` for the lifetime of the returned
+    /// guard.
+    ///
+    /// # Panics
+    ///
+    /// In debug builds, panics if there are outstanding `borrow()`s, since exclusive access
+    /// cannot be granted while the contents are concurrently readable elsewhere.
+    pub fn borrow_mut(&mut self) -> RefMut<T> {
+        debug_assert_eq!(self.borrows.get(), 0,
+                          "borrow_mut() called while a borrow() is still outstanding");
+
+        self.protect(libc::PROT_READ | libc::PROT_WRITE);
+
+        RefMut { inner: self }
     }
 
     /// Memunlock the inner data.
     fn memunlock(&self) {
         unsafe {
-            libc::munlock(&**self as *const T as *const libc::c_void,
-                          mem::size_of_val(&**self) as libc::size_t);
+            libc::munlock(self.prot_base as *const libc::c_void, self.prot_len as libc::size_t);
         };
     }
-}
 
-impl<T: ?Sized + Clone> Clone for SecBox<T> {
-    fn clone(&self) -> SecBox<T> {
+    /// Change the memory protection of the protectable (non-guard) region, verifying the canary
+    /// whenever the region becomes readable.
+    ///
+    /// # Aborts
+    ///
+    /// Aborts the process if the canary has been tampered with, since that means a buffer
+    /// over/under-run has reached into the secret.
+    fn protect(&self, prot: libc::c_int) {
         unsafe {
-            let mut bx = SecBox::new(mem::uninitialized::<T>());
-
-            // To avoid getting it outside the secure space, we clone inplace.
-            bx.clone_from(self);
+            let ret = libc::mprotect(self.prot_base as *mut libc::c_void, self.prot_len, prot);
+            assert_eq!(ret, 0, "mprotect() failed");
+        }
 
-            bx
+        if prot & libc::PROT_READ != 0 {
+            self.check_canary();
         }
     }
 
-    fn clone_from(&mut self, src: &SecBox<T>) {
-        (&mut **self).clone_from(src);
+    /// Verify that the canary guarding the secret is intact.
+    fn check_canary(&self) {
+        let actual = unsafe { slice::from_raw_parts(self.canary_ptr, CANARY_LEN) };
+        if actual != &self.canary[..] {
+            // The canary has changed: something has over/under-run into the secret. There is no
+            // safe way to continue.
+            process::abort();
+        }
     }
 }
 
-impl<T: ?Sized> From<Box<T>> for SecBox<T> {
-    fn from(from: Box<T>) -> SecBox<T> {
-        let res = SecBox {
-            inner: unsafe { Unique::new(Box::into_raw(from)) },
-        };
+/// A guard giving temporary, read-only access to the contents of a `SecBox`.
+///
+/// The backing page is `PROT_READ` for as long as this guard (and any other outstanding `Ref` to
+/// the same box) is alive. Dropping the last one reverts the page to `PROT_NONE`.
+pub struct Ref<'a, T: 'a + ?Sized> {
+    inner: &'a SecBox<T>,
+}
 
-        // Lock the data.
-        res.memlock();
+impl<'a, T: ?Sized> ops::Deref for Ref<'a, T> {
+    type Target = T;
 
-        res
+    fn deref(&self) -> &T {
+        unsafe { self.inner.inner.get() }
     }
 }
 
-impl<T: ?Sized> ops::Deref for SecBox<T> {
+impl<'a, T: ?Sized> Drop for Ref<'a, T> {
+    fn drop(&mut self) {
+        let n = self.inner.borrows.get() - 1;
+        self.inner.borrows.set(n);
+        if n == 0 {
+            self.inner.protect(libc::PROT_NONE);
+        }
+    }
+}
+
+/// A guard giving temporary, exclusive read-write access to the contents of a `SecBox`.
+///
+/// The backing page is `PROT_READ | PROT_WRITE` for as long as this guard is alive. Dropping it
+/// reverts the page to `PROT_NONE`.
+pub struct RefMut<'a, T: 'a + ?Sized> {
+    inner: &'a mut SecBox<T>,
+}
+
+impl<'a, T: ?Sized> ops::Deref for RefMut<'a, T> {
     type Target = T;
 
     fn deref(&self) -> &T {
-        unsafe { self.inner.get() }
+        unsafe { self.inner.inner.get() }
     }
 }
 
-
-impl<T: ?Sized> ops::DerefMut for SecBox<T> {
+impl<'a, T: ?Sized> ops::DerefMut for RefMut<'a, T> {
     fn deref_mut(&mut self) -> &mut T {
-        unsafe { self.inner.get_mut() }
+        unsafe { self.inner.inner.get_mut() }
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RefMut<'a, T> {
+    fn drop(&mut self) {
+        self.inner.protect(libc::PROT_NONE);
+    }
+}
+
+impl<T: ?Sized + Clone> Clone for SecBox<T> {
+    fn clone(&self) -> SecBox<T> {
+        SecBox::new(self.borrow().clone())
+    }
+
+    fn clone_from(&mut self, src: &SecBox<T>) {
+        self.borrow_mut().clone_from(&*src.borrow());
+    }
+}
+
+impl<T: ?Sized> SecBox<T> {
+    /// Fallibly convert a boxed value into a `SecBox`.
+    ///
+    /// Unlike the `From` impl, this does not panic if the allocation cannot be made or
+    /// `mlock`ed; it reports the failure instead. On failure, `from` is dropped normally.
+    pub fn try_from(from: Box<T>) -> Result<SecBox<T>, SecBoxError> {
+        let size = mem::size_of_val(&*from);
+        let align = mem::align_of_val(&*from);
+        let old_ptr = Box::into_raw(from);
+
+        unsafe {
+            let layout = match alloc_guarded(size, align) {
+                Ok(layout) => layout,
+                Err(e) => {
+                    // Give the original allocation back to `Box` so it's dropped normally.
+                    drop(Box::from_raw(old_ptr));
+                    return Err(e);
+                }
+            };
+
+            // Lock the data before anything is copied into it.
+            if let Err(e) = mlock_region(layout.prot_base, layout.prot_len) {
+                libc::munmap(layout.base as *mut libc::c_void, layout.full_len);
+                drop(Box::from_raw(old_ptr));
+                return Err(e);
+            }
+
+            // Keep the secret out of core dumps and away from forked children.
+            if let Err(e) = madvise_secure(layout.base, layout.full_len) {
+                libc::munmap(layout.base as *mut libc::c_void, layout.full_len);
+                drop(Box::from_raw(old_ptr));
+                return Err(e);
+            }
+
+            let mut canary = [0u8; CANARY_LEN];
+            if let Err(e) = fill_random(&mut canary) {
+                libc::munmap(layout.base as *mut libc::c_void, layout.full_len);
+                drop(Box::from_raw(old_ptr));
+                return Err(e);
+            }
+            ptr::copy_nonoverlapping(canary.as_ptr(), layout.canary_ptr, CANARY_LEN);
+
+            ptr::copy_nonoverlapping(old_ptr as *const u8, layout.data_ptr, size);
+
+            // The bytes now live in the secure mapping; free the original allocation without
+            // running `T`'s destructor a second time.
+            heap::deallocate(old_ptr as *mut u8, size, align);
+
+            let typed = set_data_ptr(old_ptr, layout.data_ptr);
+
+            let res = SecBox {
+                inner: Unique::new(typed),
+                base: layout.base,
+                full_len: layout.full_len,
+                prot_base: layout.prot_base,
+                prot_len: layout.prot_len,
+                canary_ptr: layout.canary_ptr,
+                canary: canary,
+                borrows: Cell::new(0),
+            };
+
+            res.protect(libc::PROT_NONE);
+
+            Ok(res)
+        }
+    }
+}
+
+impl<T: ?Sized> From<Box<T>> for SecBox<T> {
+    fn from(from: Box<T>) -> SecBox<T> {
+        SecBox::try_from(from).expect("SecBox::from: failed to secure the allocation")
     }
 }
 
@@ -178,22 +659,52 @@ impl<T: ?Sized> fmt::Debug for SecBox<T> {
     }
 }
 
+impl<T: ?Sized + AsRef<[u8]>> SecBox<T> {
+    /// Compare the contents of two `SecBox`es in constant time.
+    ///
+    /// This never branches or returns early on a mismatch, so it doesn't leak how much of the
+    /// two values matched via timing, unlike `==` on the raw bytes. Useful for comparing secrets
+    /// such as password hashes or MAC tags. Differing lengths return `false` immediately, since
+    /// the length itself is usually not a secret.
+    pub fn constant_time_eq(&self, other: &SecBox<T>) -> bool {
+        let a = self.borrow();
+        let b = other.borrow();
+        let a = a.as_ref();
+        let b = b.as_ref();
+
+        if a.len() != b.len() {
+            return false;
+        }
+
+        let mut acc = 0u8;
+        for i in 0..a.len() {
+            unsafe {
+                acc |= ptr::read_volatile(&a[i]) ^ ptr::read_volatile(&b[i]);
+            }
+        }
+
+        acc == 0
+    }
+}
+
 impl<T: ?Sized> Drop for SecBox<T> {
     fn drop(&mut self) {
         unsafe {
+            // Make the page writable so we can run the destructor and zero the memory. This also
+            // checks the canary; a tampered canary aborts before we touch the (possibly
+            // corrupted) secret any further.
+            self.protect(libc::PROT_READ | libc::PROT_WRITE);
+
+            let ptr = self.inner.get_mut() as *mut T;
             // Drop the inner data.
-            ptr::drop_in_place(*self.inner);
+            ptr::drop_in_place(ptr);
             // Zero the content.
-            intrinsics::volatile_set_memory(*self.inner as *mut u8, 0, mem::size_of_val(&**self));
+            zero_in_place(ptr);
 
-            // To avoid double-dropping, we convert our data into a byte string, which lacks of
-            // destructors.
-            let _buf = Box::from_raw(slice::from_raw_parts_mut(*self.inner as *mut u8, mem::size_of_val(&**self)));
-
-            // Unlock the memory.
+            // Unlock, undo the madvise hints, and unmap the memory.
             self.memunlock();
-
-            // _buf (the buffer) is freed.
+            madvise_unsecure(self.base, self.full_len);
+            libc::munmap(self.base as *mut libc::c_void, self.full_len);
         }
     }
 }